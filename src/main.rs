@@ -7,6 +7,9 @@ extern crate sourceview;
 extern crate rusqlite;
 extern crate glib;
 extern crate pango;
+extern crate cairo;
+extern crate gdk_pixbuf;
+extern crate notify;
 
 use gtk::prelude::*;
 
@@ -14,12 +17,401 @@ use gtk::prelude::*;
 pub enum Error {
     SQL(rusqlite::Error),
     User(String),
+    Render(String),
 }
 
 type Result<T, E=Error> = std::result::Result<T, E>;
 
+mod scheme {
+    use super::{Error, Result};
+    use std::collections::HashMap;
+
+    /// A parsed s-expression: a number, a symbol, or a parenthesised list.
+    #[derive(Clone, Debug)]
+    enum Expr {
+        Num(f64),
+        Sym(String),
+        List(Vec<Expr>),
+    }
+
+    /// The result of evaluating an `Expr`.
+    #[derive(Clone, Debug)]
+    enum Value {
+        Num(f64),
+        Bool(bool),
+        Nil,
+    }
+
+    impl Value {
+        fn as_f64(&self) -> Result<f64> {
+            match self {
+                Value::Num(n) => Ok(*n),
+                other => Err(Error::Render(format!("expected a number, got {:?}", other))),
+            }
+        }
+
+        fn is_truthy(&self) -> bool {
+            match self {
+                Value::Bool(b) => *b,
+                Value::Num(n) => *n != 0.0,
+                Value::Nil => false,
+            }
+        }
+    }
+
+    fn tokenize(source: &str) -> Vec<String> {
+        source.replace('(', " ( ").replace(')', " ) ")
+            .split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    fn parse_expr(tokens: &[String], pos: &mut usize) -> Result<Expr> {
+        let token = tokens.get(*pos)
+            .ok_or_else(|| Error::Render("unexpected end of source".to_string()))?
+            .clone();
+        *pos += 1;
+        match token.as_str() {
+            "(" => {
+                let mut items = Vec::new();
+                while tokens.get(*pos).map(String::as_str) != Some(")") {
+                    items.push(parse_expr(tokens, pos)?);
+                }
+                *pos += 1;
+                Ok(Expr::List(items))
+            }
+            ")" => Err(Error::Render("unexpected ')'".to_string())),
+            _ => Ok(token.parse::<f64>().map(Expr::Num).unwrap_or(Expr::Sym(token))),
+        }
+    }
+
+    /// Parse `source` as a sequence of top-level forms.
+    fn parse(source: &str) -> Result<Vec<Expr>> {
+        let tokens = tokenize(source);
+        let mut pos = 0;
+        let mut forms = Vec::new();
+        while pos < tokens.len() {
+            forms.push(parse_expr(&tokens, &mut pos)?);
+        }
+        Ok(forms)
+    }
+
+    /// Variable bindings in scope. `let` forks a copy rather than chaining
+    /// parent scopes, which is simple and is all this minimal subset needs.
+    struct Env {
+        vars: HashMap<String, Value>,
+    }
+
+    fn eval(expr: &Expr, env: &mut Env, ctx: &cairo::Context) -> Result<Value> {
+        match expr {
+            Expr::Num(n) => Ok(Value::Num(*n)),
+            Expr::Sym(name) => env.vars.get(name).cloned()
+                .ok_or_else(|| Error::Render(format!("unbound variable: {}", name))),
+            Expr::List(items) => eval_list(items, env, ctx),
+        }
+    }
+
+    /// Look up `items[i]`, or fail loudly instead of panicking when `form`
+    /// was called with too few operands.
+    fn item<'a>(items: &'a [Expr], i: usize, form: &str) -> Result<&'a Expr> {
+        items.get(i).ok_or_else(|| Error::Render(format!("{}: too few arguments", form)))
+    }
+
+    fn eval_list(items: &[Expr], env: &mut Env, ctx: &cairo::Context) -> Result<Value> {
+        if items.is_empty() {
+            return Ok(Value::Nil);
+        }
+        if let Expr::Sym(head) = &items[0] {
+            match head.as_str() {
+                "define" => {
+                    let name = match item(items, 1, "define")? {
+                        Expr::Sym(name) => name.clone(),
+                        _ => return Err(Error::Render("define: expected a variable name".to_string())),
+                    };
+                    let value = eval(item(items, 2, "define")?, env, ctx)?;
+                    env.vars.insert(name, value);
+                    return Ok(Value::Nil);
+                }
+                "let" => {
+                    let bindings = match item(items, 1, "let")? {
+                        Expr::List(bindings) => bindings,
+                        _ => return Err(Error::Render("let: expected a binding list".to_string())),
+                    };
+                    let mut inner = Env { vars: env.vars.clone() };
+                    for binding in bindings {
+                        match binding {
+                            Expr::List(pair) if pair.len() == 2 => {
+                                if let Expr::Sym(name) = &pair[0] {
+                                    let value = eval(&pair[1], env, ctx)?;
+                                    inner.vars.insert(name.clone(), value);
+                                    continue;
+                                }
+                                return Err(Error::Render("let: expected a variable name".to_string()));
+                            }
+                            _ => return Err(Error::Render("let: expected (name value) bindings".to_string())),
+                        }
+                    }
+                    let mut result = Value::Nil;
+                    for body in &items[2..] {
+                        result = eval(body, &mut inner, ctx)?;
+                    }
+                    return Ok(result);
+                }
+                "if" => {
+                    let cond = eval(item(items, 1, "if")?, env, ctx)?;
+                    return if cond.is_truthy() {
+                        eval(item(items, 2, "if")?, env, ctx)
+                    } else if items.len() > 3 {
+                        eval(&items[3], env, ctx)
+                    } else {
+                        Ok(Value::Nil)
+                    };
+                }
+                "begin" => {
+                    let mut result = Value::Nil;
+                    for body in &items[1..] {
+                        result = eval(body, env, ctx)?;
+                    }
+                    return Ok(result);
+                }
+                "repeat" => {
+                    let count = eval(item(items, 1, "repeat")?, env, ctx)?.as_f64()? as i64;
+                    let mut result = Value::Nil;
+                    for _ in 0..count.max(0) {
+                        for body in &items[2..] {
+                            result = eval(body, env, ctx)?;
+                        }
+                    }
+                    return Ok(result);
+                }
+                _ => {}
+            }
+        }
+        let name = match &items[0] {
+            Expr::Sym(name) => name.as_str(),
+            _ => return Err(Error::Render("expected a procedure name".to_string())),
+        };
+        let mut args = Vec::with_capacity(items.len() - 1);
+        for arg in &items[1..] {
+            args.push(eval(arg, env, ctx)?);
+        }
+        apply(name, &args, ctx)
+    }
+
+    /// The builtin procedures this subset understands: arithmetic,
+    /// comparisons, and the drawing primitives that reach `ctx` directly.
+    fn apply(name: &str, args: &[Value], ctx: &cairo::Context) -> Result<Value> {
+        fn num(v: &Value) -> Result<f64> { v.as_f64() }
+        fn nums(args: &[Value]) -> Result<Vec<f64>> { args.iter().map(num).collect() }
+        /// Look up `args[i]`, or fail loudly instead of panicking when
+        /// `name` was called with too few arguments.
+        fn arg<'a>(args: &'a [Value], i: usize, name: &str) -> Result<&'a Value> {
+            args.get(i).ok_or_else(|| Error::Render(format!("{}: too few arguments", name)))
+        }
+
+        match name {
+            "+" => Ok(Value::Num(nums(args)?.iter().sum())),
+            "*" => Ok(Value::Num(nums(args)?.iter().product())),
+            "-" => {
+                let n = nums(args)?;
+                if n.is_empty() {
+                    return Err(Error::Render("-: too few arguments".to_string()));
+                }
+                Ok(Value::Num(match n.len() {
+                    1 => -n[0],
+                    _ => n[1..].iter().fold(n[0], |a, b| a - b),
+                }))
+            }
+            "/" => {
+                let n = nums(args)?;
+                if n.is_empty() {
+                    return Err(Error::Render("/: too few arguments".to_string()));
+                }
+                Ok(Value::Num(n[1..].iter().fold(n[0], |a, b| a / b)))
+            }
+            "<" => Ok(Value::Bool(num(arg(args, 0, "<")?)? < num(arg(args, 1, "<")?)?)),
+            ">" => Ok(Value::Bool(num(arg(args, 0, ">")?)? > num(arg(args, 1, ">")?)?)),
+            "=" => Ok(Value::Bool(num(arg(args, 0, "=")?)? == num(arg(args, 1, "=")?)?)),
+            "sin" => Ok(Value::Num(num(arg(args, 0, "sin")?)?.sin())),
+            "cos" => Ok(Value::Num(num(arg(args, 0, "cos")?)?.cos())),
+            "set-background" => {
+                ctx.set_source_rgb(
+                    num(arg(args, 0, "set-background")?)?,
+                    num(arg(args, 1, "set-background")?)?,
+                    num(arg(args, 2, "set-background")?)?);
+                ctx.paint();
+                Ok(Value::Nil)
+            }
+            "set-color" => {
+                ctx.set_source_rgb(
+                    num(arg(args, 0, "set-color")?)?,
+                    num(arg(args, 1, "set-color")?)?,
+                    num(arg(args, 2, "set-color")?)?);
+                Ok(Value::Nil)
+            }
+            "circle" => {
+                ctx.arc(
+                    num(arg(args, 0, "circle")?)?,
+                    num(arg(args, 1, "circle")?)?,
+                    num(arg(args, 2, "circle")?)?,
+                    0.0, 2.0 * std::f64::consts::PI);
+                ctx.fill();
+                Ok(Value::Nil)
+            }
+            "rect" => {
+                ctx.rectangle(
+                    num(arg(args, 0, "rect")?)?,
+                    num(arg(args, 1, "rect")?)?,
+                    num(arg(args, 2, "rect")?)?,
+                    num(arg(args, 3, "rect")?)?);
+                ctx.fill();
+                Ok(Value::Nil)
+            }
+            "line" => {
+                ctx.move_to(num(arg(args, 0, "line")?)?, num(arg(args, 1, "line")?)?);
+                ctx.line_to(num(arg(args, 2, "line")?)?, num(arg(args, 3, "line")?)?);
+                ctx.stroke();
+                Ok(Value::Nil)
+            }
+            other => Err(Error::Render(format!("unknown procedure: {}", other))),
+        }
+    }
+
+    /// Evaluate `source` as a small Scheme-like program and paint its
+    /// drawing calls (`circle`, `rect`, `line`, `set-color`,
+    /// `set-background`) onto `ctx`. This only covers the subset of
+    /// Scheme needed for object previews — arithmetic, `define`/`let`,
+    /// `if`/`begin`/`repeat`, and the builtins above. A source that uses
+    /// anything outside that subset fails loudly via `Error::Render`
+    /// rather than silently rendering a placeholder.
+    pub fn paint(ctx: &cairo::Context, source: &str, width: i32, height: i32) -> Result<()> {
+        ctx.set_source_rgb(1.0, 1.0, 1.0);
+        ctx.paint();
+
+        let mut env = Env { vars: HashMap::new() };
+        env.vars.insert("width".to_string(), Value::Num(width as f64));
+        env.vars.insert("height".to_string(), Value::Num(height as f64));
+
+        for form in parse(source)? {
+            eval(&form, &mut env, ctx)?;
+        }
+        ctx.status().map_err(|e| Error::Render(format!("{:?}", e)))
+    }
+}
+
+mod render {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::{channel, Sender, Receiver};
+    use std::thread;
+
+    use super::{Error, Result, scheme};
+
+    const POOL_SIZE: usize = 4;
+    pub const THUMB_SIZE: i32 = 128;
+
+    pub struct Job {
+        pub id: i64,
+        pub source: String,
+    }
+
+    pub struct Rendered {
+        pub id: i64,
+        pub png: Vec<u8>,
+    }
+
+    /// A fixed-size worker pool that renders thumbnails off the state
+    /// thread. Jobs for the same object id are generation-tagged so a
+    /// rapid edit invalidates any still in-flight render for the old
+    /// source rather than letting it overwrite a newer result.
+    pub struct Pool {
+        tx_job: Sender<(Job, u64)>,
+        generation: Arc<Mutex<HashMap<i64, u64>>>,
+    }
+
+    impl Pool {
+        pub fn new(tx_done: Sender<Rendered>) -> Pool {
+            let (tx_job, rx_job) = channel::<(Job, u64)>();
+            let rx_job = Arc::new(Mutex::new(rx_job));
+            let generation = Arc::new(Mutex::new(HashMap::new()));
+            for _ in 0..POOL_SIZE {
+                let rx_job = rx_job.clone();
+                let tx_done = tx_done.clone();
+                let generation = generation.clone();
+                thread::spawn(move || loop {
+                    let next = { rx_job.lock().unwrap().recv() };
+                    let (job, gen) = match next {
+                        Ok(j) => j,
+                        Err(_) => break,
+                    };
+                    match render_thumbnail(&job.source) {
+                        Ok(png) => {
+                            let current = *generation.lock().unwrap().get(&job.id).unwrap_or(&0);
+                            if current == gen {
+                                tx_done.send(Rendered { id: job.id, png }).ok();
+                            }
+                        }
+                        Err(e) => log::warn!("thumbnail render failed for {}: {:?}", job.id, e),
+                    }
+                });
+            }
+            Pool { tx_job, generation }
+        }
+
+        pub fn execute(&self, job: Job) {
+            let mut generations = self.generation.lock().unwrap();
+            let gen = generations.entry(job.id).or_insert(0);
+            *gen += 1;
+            let gen = *gen;
+            drop(generations);
+            self.tx_job.send((job, gen)).ok();
+        }
+    }
+
+    fn render_thumbnail(source: &str) -> Result<Vec<u8>> {
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, THUMB_SIZE, THUMB_SIZE)
+            .map_err(|e| Error::Render(format!("{:?}", e)))?;
+        {
+            let ctx = cairo::Context::new(&surface);
+            scheme::paint(&ctx, source, THUMB_SIZE, THUMB_SIZE)?;
+        }
+        let mut png = Vec::new();
+        surface.write_to_png(&mut png).map_err(|e| Error::Render(e.to_string()))?;
+        Ok(png)
+    }
+
+    pub fn pixbuf_from_png(bytes: &[u8]) -> Result<gdk_pixbuf::Pixbuf> {
+        let loader = gdk_pixbuf::PixbufLoader::new();
+        loader.write(bytes).map_err(|e| Error::Render(e.to_string()))?;
+        loader.close().map_err(|e| Error::Render(e.to_string()))?;
+        loader.get_pixbuf().ok_or_else(|| Error::Render("empty PNG buffer".to_string()))
+    }
+
+    pub const EXPORT_SIZE: i32 = 1024;
+
+    pub fn render_to_png(source: &str, path: &std::path::Path, width: i32, height: i32) -> Result<()> {
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height)
+            .map_err(|e| Error::Render(format!("{:?}", e)))?;
+        {
+            let ctx = cairo::Context::new(&surface);
+            scheme::paint(&ctx, source, width, height)?;
+        }
+        let mut file = std::fs::File::create(path).map_err(|e| Error::Render(e.to_string()))?;
+        surface.write_to_png(&mut file).map_err(|e| Error::Render(e.to_string()))
+    }
+
+    pub fn render_to_svg(source: &str, path: &std::path::Path, width: i32, height: i32) -> Result<()> {
+        let surface = cairo::SvgSurface::new(width as f64, height as f64, Some(path))
+            .map_err(|e| Error::Render(format!("{:?}", e)))?;
+        let ctx = cairo::Context::new(&surface);
+        scheme::paint(&ctx, source, width, height)?;
+        surface.finish();
+        Ok(())
+    }
+}
+
 mod ui {
+    use std::cell::{Cell, RefCell};
     use std::collections::HashMap;
+    use std::rc::Rc;
     use gtk::prelude::*;
     use glib::clone;
     use sourceview::prelude::*;
@@ -34,6 +426,8 @@ mod ui {
         ClearItems,
         NewItem(ItemInfo),
         SetSource(String),
+        SetThumbnail(i64, gdk_pixbuf::Pixbuf),
+        SetDirty(bool),
     }
 
     pub struct ItemInfo {
@@ -44,11 +438,16 @@ mod ui {
     }
 
     pub struct App {
-        builder:    gtk::Builder,
-        window:     gtk::Window,
-        code_view:  sourceview::View,
-        header_bar: gtk::HeaderBar,
-        item_list:  gtk::ListBox,
+        builder:        gtk::Builder,
+        window:         gtk::Window,
+        code_view:      sourceview::View,
+        header_bar:     gtk::HeaderBar,
+        item_list:      gtk::ListBox,
+        item_search:    gtk::SearchEntry,
+        thumbnails:     RefCell<HashMap<i64, gtk::Image>>,
+        changed_handler: RefCell<Option<glib::SignalHandlerId>>,
+        current_file:   Rc<RefCell<Option<PathBuf>>>,
+        dirty:          Cell<bool>,
     }
 
     impl App {
@@ -60,6 +459,7 @@ mod ui {
             let header_bar: gtk::HeaderBar = builder.get_object("header").unwrap();
             let code_view: sourceview::View = builder.get_object("code_view").unwrap();
             let item_list: gtk::ListBox = builder.get_object("item_list").unwrap();
+            let item_search: gtk::SearchEntry = builder.get_object("item_search").unwrap();
 
             log::debug!("creating code buffer");
             init_code_buffer(&code_view);
@@ -67,18 +467,39 @@ mod ui {
 
             App {
                 builder: builder, window: window, code_view: code_view,
-                header_bar: header_bar, item_list: item_list
+                header_bar: header_bar, item_list: item_list,
+                item_search: item_search,
+                thumbnails: RefCell::new(HashMap::new()),
+                changed_handler: RefCell::new(None),
+                current_file: Rc::new(RefCell::new(None)),
+                dirty: Cell::new(false),
             }
         }
 
         pub fn clear_items(&self)
         {
             self.item_list.foreach(|x| self.item_list.remove(x));
+            self.thumbnails.borrow_mut().clear();
+        }
+
+        fn update_title(&self)
+        {
+            let name = self.current_file.borrow().as_ref()
+                .and_then(|p| p.file_name()).and_then(|s| s.to_str())
+                .unwrap_or("New Project").to_string();
+            let marker = if self.dirty.get() { " *" } else { "" };
+            self.header_bar.set_title(Some(format!("{}{}", name, marker).as_str()));
         }
 
 
         pub fn connect(&self, tx_state: Sender<state::Msg>) -> Result<()>
         {
+            let buffer = self.code_view.get_buffer().unwrap();
+            let handler = buffer.connect_changed(clone!(@strong tx_state => move |_| {
+                tx_state.send(state::Msg::EditorChanged).unwrap();
+            }));
+            *self.changed_handler.borrow_mut() = Some(handler);
+
             self.builder.connect_signals(|_, signal_name| {
                 match signal_name {
                     "on_window_destroy" => {
@@ -92,7 +513,7 @@ mod ui {
                         log::debug!("Connecting on_button_clicked signal");
                         let _window = self.window.clone();
                         Box::new(clone!(@strong tx_state => move |_| {
-                            let path = select_file_dialog(&_window);
+                            let path = select_open_file_dialog(&_window);
                             if path.is_none() { return None; }
                             tx_state.send(state::Msg::Open(path.unwrap())).unwrap();
                             None
@@ -102,6 +523,57 @@ mod ui {
                         Box::new(clone!(@strong tx_state => move |_| {
                             tx_state.send(state::Msg::NewItem).unwrap(); None }))
                     }
+                    "save_button_clicked" => {
+                        let buffer = self.code_view.get_buffer().unwrap();
+                        let window = self.window.clone();
+                        let current_file = self.current_file.clone();
+                        Box::new(clone!(@strong tx_state => move |_| {
+                            let (start, end) = buffer.get_bounds();
+                            let text = buffer.get_text(&start, &end, false)
+                                .unwrap().to_string();
+                            tx_state.send(state::Msg::StoreCode(text)).unwrap();
+                            let path = current_file.borrow().clone();
+                            match path {
+                                Some(_) => { tx_state.send(state::Msg::Save).unwrap(); }
+                                None => {
+                                    if let Some(p) = select_save_file_dialog(&window) {
+                                        tx_state.send(state::Msg::SaveAs(p)).unwrap();
+                                    }
+                                }
+                            }
+                            None
+                        }))
+                    }
+                    "save_as_button_clicked" => {
+                        let buffer = self.code_view.get_buffer().unwrap();
+                        let window = self.window.clone();
+                        Box::new(clone!(@strong tx_state => move |_| {
+                            let (start, end) = buffer.get_bounds();
+                            let text = buffer.get_text(&start, &end, false)
+                                .unwrap().to_string();
+                            tx_state.send(state::Msg::StoreCode(text)).unwrap();
+                            if let Some(p) = select_save_file_dialog(&window) {
+                                tx_state.send(state::Msg::SaveAs(p)).unwrap();
+                            }
+                            None
+                        }))
+                    }
+                    "export_button_clicked" => {
+                        let window = self.window.clone();
+                        Box::new(clone!(@strong tx_state => move |_| {
+                            if let Some(dir) = select_export_folder_dialog(&window) {
+                                tx_state.send(state::Msg::ExportAll(dir, false)).unwrap();
+                            }
+                            None
+                        }))
+                    }
+                    "item_search" => {
+                        let search_entry = self.item_search.clone();
+                        Box::new(clone!(@strong tx_state => move |_| {
+                            let query = search_entry.get_text().to_string();
+                            tx_state.send(state::Msg::Search(query)).unwrap();
+                            None }))
+                    }
                     "item_select" => {
                         let buffer = self.code_view.get_buffer().unwrap();
                         let list_box = self.item_list.clone();
@@ -128,21 +600,38 @@ mod ui {
         pub fn handle(&self, msg: Msg) -> glib::Continue {
             match msg {
                 Msg::SetFilename(path) => {
-                    self.header_bar.set_title(path.file_name().and_then(|s| s.to_str()));
                     self.header_bar.set_subtitle(path.parent().and_then(|p| p.to_str()));
+                    *self.current_file.borrow_mut() = Some(path);
+                    self.update_title();
                 }
                 Msg::ClearItems => {
                     self.clear_items()
                 }
                 Msg::NewItem(info) => {
-                    let widget = create_card(info.name, info.description);
+                    let (widget, thumb) = create_card(info.name, info.description);
                     unsafe { widget.set_data("db_id", info.id); }
                     self.item_list.insert(&widget, -1);
                     self.item_list.show_all();
+                    self.thumbnails.borrow_mut().insert(info.id, thumb);
                 }
                 Msg::SetSource(text) => {
                     let buffer = self.code_view.get_buffer().unwrap();
-                    buffer.set_text(text.as_str());
+                    if let Some(handler) = self.changed_handler.borrow().as_ref() {
+                        glib::signal::signal_handler_block(&buffer, handler);
+                        buffer.set_text(text.as_str());
+                        glib::signal::signal_handler_unblock(&buffer, handler);
+                    } else {
+                        buffer.set_text(text.as_str());
+                    }
+                }
+                Msg::SetThumbnail(id, pixbuf) => {
+                    if let Some(image) = self.thumbnails.borrow().get(&id) {
+                        image.set_from_pixbuf(Some(&pixbuf));
+                    }
+                }
+                Msg::SetDirty(dirty) => {
+                    self.dirty.set(dirty);
+                    self.update_title();
                 }
             }
             glib::Continue(true)
@@ -165,7 +654,7 @@ mod ui {
         code_buffer
     }
 
-    fn create_card(name: String, description: Option<String>) -> impl IsA<gtk::Widget> {
+    fn create_card(name: String, description: Option<String>) -> (impl IsA<gtk::Widget>, gtk::Image) {
         use gtk::{Orientation,IconSize};
         let outer = gtk::Box::new(Orientation::Vertical, 0);
         let row1 = gtk::Box::new(Orientation::Horizontal, 0);
@@ -184,15 +673,39 @@ mod ui {
         row1.pack_start(&destr, false, true, 0);
         row2.pack_start(&descr, true, true, 5);
         row2.pack_start(&thumb, false, true, 0);
-        outer
+        (outer, thumb)
     }
 
-    fn select_file_dialog(w: &gtk::Window) -> Option<std::path::PathBuf> {
+    fn select_open_file_dialog(w: &gtk::Window) -> Option<std::path::PathBuf> {
         let dialog = gtk::FileChooserDialog::with_buttons(
-            Some("Open File"), Some(w), gtk::FileChooserAction::Save,
+            Some("Open File"), Some(w), gtk::FileChooserAction::Open,
             &[("_Cancel", gtk::ResponseType::Cancel), ("_Open", gtk::ResponseType::Accept)]);
         let result = match dialog.run() {
-            gtk::ResponseType::Cancel => None,
+            gtk::ResponseType::Accept => dialog.get_filename(),
+            _                         => None
+        };
+        unsafe { dialog.destroy(); }
+        result
+    }
+
+    fn select_save_file_dialog(w: &gtk::Window) -> Option<std::path::PathBuf> {
+        let dialog = gtk::FileChooserDialog::with_buttons(
+            Some("Save File"), Some(w), gtk::FileChooserAction::Save,
+            &[("_Cancel", gtk::ResponseType::Cancel), ("_Save", gtk::ResponseType::Accept)]);
+        dialog.set_do_overwrite_confirmation(true);
+        let result = match dialog.run() {
+            gtk::ResponseType::Accept => dialog.get_filename(),
+            _                         => None
+        };
+        unsafe { dialog.destroy(); }
+        result
+    }
+
+    fn select_export_folder_dialog(w: &gtk::Window) -> Option<std::path::PathBuf> {
+        let dialog = gtk::FileChooserDialog::with_buttons(
+            Some("Export To…"), Some(w), gtk::FileChooserAction::SelectFolder,
+            &[("_Cancel", gtk::ResponseType::Cancel), ("_Export", gtk::ResponseType::Accept)]);
+        let result = match dialog.run() {
             gtk::ResponseType::Accept => dialog.get_filename(),
             _                         => None
         };
@@ -205,16 +718,69 @@ mod ui {
 mod state {
     use std::path::{Path,PathBuf};
     use rusqlite::{Connection};
-    use std::sync::mpsc::{Receiver};
-    use super::{Error, Result, ui};
+    use std::sync::mpsc::{Receiver, Sender};
+    use super::{Error, Result, ui, render};
     use std::collections::HashSet;
     use rusqlite::params;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use notify::{Watcher, RecommendedWatcher, RecursiveMode, DebouncedEvent};
 
-    const SCHEMA : &str = std::include_str!("../data/schema.sqlite");
+    const MIGRATIONS: &[(u32, &str)] = &[
+        (1, std::include_str!("../data/migrations/001_create_objects.sql")),
+        (2, std::include_str!("../data/migrations/002_add_thumbnail.sql")),
+        (3, std::include_str!("../data/migrations/003_add_fts_search.sql")),
+    ];
+
+    fn table_exists(conn: &Connection, name: &str) -> Result<bool> {
+        conn.query_row(
+            "select count(*) from `sqlite_master` where `type` = 'table' and `name` = ?1",
+            params![name], |r| r.get::<_, i64>(0))
+            .map(|n| n > 0)
+            .map_err(Error::SQL)
+    }
+
+    /// A database can report `user_version = 0` and still have tables:
+    /// project files saved before this migration system existed created
+    /// `objects`/`objects_fts` directly, with no version pragma set at
+    /// all. Inspect `sqlite_master` to tell that apart from a genuinely
+    /// empty database, so `migrate` doesn't try to recreate tables that
+    /// are already there.
+    fn detect_legacy_version(conn: &Connection) -> Result<u32> {
+        if !table_exists(conn, "objects")? {
+            return Ok(0);
+        }
+        if table_exists(conn, "objects_fts")? {
+            return Ok(3);
+        }
+        let has_thumbnail = conn.prepare("select `thumbnail` from `objects` limit 0").is_ok();
+        Ok(if has_thumbnail { 2 } else { 1 })
+    }
+
+    /// Bring `conn` up to the newest schema version, running only the
+    /// migrations newer than its current `user_version`. Safe to call on
+    /// a freshly created database as well as an older project file.
+    pub fn migrate(conn: &Connection) -> Result<()> {
+        let reported: u32 = conn.query_row("pragma user_version", params![], |r| r.get(0))
+            .map_err(Error::SQL)?;
+        let current = if reported == 0 { detect_legacy_version(conn)? } else { reported };
+        if current != reported {
+            conn.execute_batch(&format!("pragma user_version = {}", current)).map_err(Error::SQL)?;
+        }
+        for (version, sql) in MIGRATIONS {
+            if *version <= current { continue; }
+            let tx = conn.unchecked_transaction().map_err(Error::SQL)?;
+            tx.execute_batch(sql).map_err(Error::SQL)?;
+            tx.execute_batch(&format!("pragma user_version = {}", version)).map_err(Error::SQL)?;
+            tx.commit().map_err(Error::SQL)?;
+        }
+        Ok(())
+    }
 
     fn open_file<P: AsRef<Path>>(path: P) -> Result<Connection> {
         let conn = Connection::open(path).map_err(Error::SQL)?;
-        conn.execute_batch(&SCHEMA).map_err(Error::SQL)?;
+        migrate(&conn)?;
         Ok(conn)
     }
 
@@ -224,17 +790,103 @@ mod state {
         conn.backup(Main, path, None::<fn(Progress)>).map_err(Error::SQL)
     }
 
+    /// Watch `path` for external changes and post `Msg::Reload` when it is
+    /// written to, debounced so a burst of writes collapses into one
+    /// reload. Returns `None` (rather than erroring) if the watcher could
+    /// not be set up, since hot-reload is a convenience, not a requirement.
+    ///
+    /// Watches the parent directory rather than `path` itself: editors
+    /// that save atomically (write a temp file, then rename it over the
+    /// original) replace the inode at `path`, and inotify watches on a
+    /// single file don't follow that rename — the watch would silently
+    /// stop firing after the first external edit. Watching the directory
+    /// and filtering by filename survives the rename.
+    fn watch_path<P: AsRef<Path>>(path: P, tx_self: Sender<Msg>, suppress_watch: Arc<AtomicBool>)
+        -> Option<RecommendedWatcher>
+    {
+        let path = path.as_ref();
+        let file_name = path.file_name()?.to_owned();
+        let dir = path.parent().filter(|p| !p.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+
+        let (tx_fs, rx_fs) = std::sync::mpsc::channel();
+        let mut watcher = notify::watcher(tx_fs, Duration::from_millis(500)).ok()?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive).ok()?;
+        std::thread::spawn(move || {
+            for event in rx_fs {
+                if suppress_watch.load(Ordering::SeqCst) { continue; }
+                let changed = match &event {
+                    DebouncedEvent::Write(p) | DebouncedEvent::Create(p) => Some(p),
+                    _ => None,
+                };
+                if changed.and_then(|p| p.file_name()) != Some(file_name.as_os_str()) {
+                    continue;
+                }
+                if tx_self.send(Msg::Reload).is_err() { break; }
+            }
+        });
+        Some(watcher)
+    }
+
+    fn sanitize_filename(name: &str) -> String {
+        name.chars().map(|c| if c == '/' || c == '\\' { '_' } else { c }).collect()
+    }
+
+    /// Render every object to `dir`. A single object that fails to render
+    /// (an unsupported or broken source) shouldn't block the rest of the
+    /// batch, so failures are logged and skipped; this only returns `Err`
+    /// if nothing could be exported at all.
+    fn export_objects(conn: &Connection, dir: &Path, svg: bool) -> Result<()> {
+        std::fs::create_dir_all(dir).map_err(|e| Error::Render(e.to_string()))?;
+        let mut stmt = conn.prepare("select `name`, `source` from `objects`")
+            .map_err(Error::SQL)?;
+        let objects: Vec<(String, String)> = stmt.query_map(params![], |r| Ok((r.get(0)?, r.get(1)?)))
+            .map_err(Error::SQL)?
+            .collect::<rusqlite::Result<Vec<_>>>().map_err(Error::SQL)?;
+        let total = objects.len();
+        let mut exported = 0;
+        for (name, source) in objects {
+            let ext = if svg { "svg" } else { "png" };
+            let path = dir.join(format!("{}.{}", sanitize_filename(&name), ext));
+            let result = if svg {
+                render::render_to_svg(&source, &path, render::EXPORT_SIZE, render::EXPORT_SIZE)
+            } else {
+                render::render_to_png(&source, &path, render::EXPORT_SIZE, render::EXPORT_SIZE)
+            };
+            match result {
+                Ok(()) => exported += 1,
+                Err(e) => log::warn!("failed to export '{}': {:?}", name, e),
+            }
+        }
+        if total > 0 && exported == 0 {
+            return Err(Error::Render("no objects could be exported".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Render every object in `project` to `out_dir` without starting the
+    /// GUI, for scripted batch export via `--export`.
+    pub fn export_headless(project: &Path, out_dir: &Path, svg: bool) -> Result<()> {
+        let conn = open_file(project)?;
+        export_objects(&conn, out_dir, svg)
+    }
+
     fn new_project() -> Result<Connection> {
         let conn = Connection::open_in_memory().map_err(Error::SQL)?;
-        let schema = std::include_str!("../data/schema.sqlite");
-        conn.execute_batch(&schema).map_err(Error::SQL)?;
+        migrate(&conn)?;
         Ok(conn)
-    } 
+    }
 
     pub struct State {
         open_file: Option<PathBuf>,
         conn: Connection,
         active_object: Option<i64>,
+        render_pool: render::Pool,
+        tx_self: Sender<Msg>,
+        suppress_watch: Arc<AtomicBool>,
+        editor_dirty: bool,
+        _watcher: Option<RecommendedWatcher>,
     }
 
     pub enum Msg {
@@ -245,13 +897,36 @@ mod state {
         SelectItem(i64),
         SetDescription(String),
         SetName(String),
+        ThumbnailRendered(i64, Vec<u8>),
+        Search(String),
+        EditorChanged,
+        Reload,
+        Save,
+        SaveAs(PathBuf),
+        ExportAll(PathBuf, bool),
     }
 
     impl State {
-        pub fn new() -> State
+        pub fn new(tx_self: Sender<Msg>) -> State
         {
             let conn = new_project().unwrap();
-            State { open_file: None, conn: conn, active_object: None }
+            let (tx_rendered, rx_rendered) = std::sync::mpsc::channel();
+            let tx_rendered_self = tx_self.clone();
+            std::thread::spawn(move || {
+                for rendered in rx_rendered {
+                    if tx_rendered_self.send(Msg::ThumbnailRendered(rendered.id, rendered.png)).is_err() {
+                        break;
+                    }
+                }
+            });
+            State {
+                open_file: None, conn: conn, active_object: None,
+                render_pool: render::Pool::new(tx_rendered),
+                tx_self: tx_self,
+                suppress_watch: Arc::new(AtomicBool::new(false)),
+                editor_dirty: false,
+                _watcher: None,
+            }
         }
 
         fn open<P: AsRef<Path>>(&mut self, path: P) -> Result<()>
@@ -259,13 +934,33 @@ mod state {
             let conn = open_file(path.as_ref())?;
             log::info!("Loaded {:}.", path.as_ref().display());
             self.conn = conn;
+            self._watcher = watch_path(path.as_ref(), self.tx_self.clone(), self.suppress_watch.clone());
             Ok(())
         }
 
+        /// Run `f`, which writes to the open file, while suppressing the
+        /// filesystem watcher so our own write doesn't trigger a reload.
+        fn suppressing_watch<F: FnOnce(&mut Self) -> Result<()>>(&mut self, f: F) -> Result<()> {
+            self.suppress_watch.store(true, Ordering::SeqCst);
+            let result = f(self);
+            let suppress_watch = self.suppress_watch.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(750));
+                suppress_watch.store(false, Ordering::SeqCst);
+            });
+            result
+        }
+
         fn save_as(&mut self, path: PathBuf) -> Result<()>
         {
-            save_file(&mut self.conn, &path)?;
-            self.open(path)
+            self.suppressing_watch(|state| {
+                save_file(&mut state.conn, &path)?;
+                state.open(path)
+            })
+        }
+
+        fn export_all(&self, dir: &Path, svg: bool) -> Result<()> {
+            export_objects(&self.conn, dir, svg)
         }
 
         fn unique_name(&self) -> Result<String>
@@ -303,13 +998,15 @@ mod state {
             Ok(())
         }
 
-        fn update_source(&self, source: &String) -> Result<()> {
+        fn update_source(&mut self, source: &String) -> Result<()> {
             let id = self.active_object
                 .ok_or(Error::User("No object selected.".to_string()))?;
-            self.conn.execute(
-                "update `objects` set `source` = ?2 where `id` = ?1",
-                params![id, source]).map_err(Error::SQL)?;
-            Ok(())
+            self.suppressing_watch(|state| {
+                state.conn.execute(
+                    "update `objects` set `source` = ?2 where `id` = ?1",
+                    params![id, source]).map_err(Error::SQL)?;
+                Ok(())
+            })
         }
 
         fn update_description(&self, descr: &String) -> Result<()> {
@@ -338,6 +1035,54 @@ mod state {
             stmt.query_row(params![id], |r| r.get(0)).map_err(Error::SQL)
         }
 
+        fn store_thumbnail(&self, id: i64, png: &[u8]) -> Result<()> {
+            self.conn.execute(
+                "update `objects` set `thumbnail` = ?2 where `id` = ?1",
+                params![id, png]).map_err(Error::SQL)?;
+            Ok(())
+        }
+
+        /// Returns `(id, name, description, thumbnail)` for every object
+        /// matching `query` (or every object, if `query` is blank). The
+        /// thumbnail is fetched alongside the rest of the row so callers
+        /// that rebuild the whole item list can redisplay it without
+        /// waiting for a fresh render.
+        fn search(&self, query: &str) -> Result<Vec<(i64, String, Option<String>, Option<Vec<u8>>)>> {
+            if query.trim().is_empty() {
+                let mut stmt = self.conn.prepare(
+                    "select `id`, `name`, `description`, `thumbnail` from `objects` order by `name`")
+                    .map_err(Error::SQL)?;
+                return stmt.query_map(params![], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))
+                    .map_err(Error::SQL)?
+                    .collect::<rusqlite::Result<Vec<_>>>().map_err(Error::SQL);
+            }
+            let mut stmt = self.conn.prepare(
+                "select `o`.`id`, `o`.`name`, `o`.`description`, `o`.`thumbnail` \
+                 from `objects_fts` `f` join `objects` `o` on `o`.`id` = `f`.`rowid` \
+                 where `f` match ?1 order by rank")
+                .map_err(Error::SQL)?;
+            stmt.query_map(params![query], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))
+                .map_err(Error::SQL)?
+                .collect::<rusqlite::Result<Vec<_>>>().map_err(Error::SQL)
+        }
+
+        /// Replace the item list in the UI with `hits`, restoring each
+        /// item's persisted thumbnail instead of leaving it blank until
+        /// the item is reselected or re-rendered.
+        fn emit_items(tx_event: &glib::Sender<ui::Msg>, hits: Vec<(i64, String, Option<String>, Option<Vec<u8>>)>) {
+            tx_event.send(ui::Msg::ClearItems).unwrap();
+            for (id, name, description, thumbnail) in hits {
+                tx_event.send(ui::Msg::NewItem(ui::ItemInfo {
+                    id: id, name: name, description: description, thumbnail: () })).unwrap();
+                if let Some(png) = thumbnail {
+                    match render::pixbuf_from_png(&png) {
+                        Ok(pixbuf) => { tx_event.send(ui::Msg::SetThumbnail(id, pixbuf)).unwrap(); }
+                        Err(e) => log::warn!("{:?}", e),
+                    }
+                }
+            }
+        }
+
         pub fn listen(&mut self, tx_event: glib::Sender<ui::Msg>, rx: Receiver<Msg>) {
             for msg in rx {
                 match msg {
@@ -355,8 +1100,19 @@ mod state {
                             id: id, name: name, description: description, thumbnail: () })).unwrap();
                     }
                     Msg::StoreCode(text) => {
-                        self.update_source(&text).unwrap_or_else(|e| {
-                            log::warn!("{:?}", e); });
+                        if self.update_source(&text).is_ok() {
+                            self.editor_dirty = false;
+                            tx_event.send(ui::Msg::SetDirty(false)).unwrap();
+                            if let Some(id) = self.active_object {
+                                self.render_pool.execute(render::Job { id, source: text });
+                            }
+                        } else {
+                            log::warn!("failed to store source");
+                        }
+                    }
+                    Msg::EditorChanged => {
+                        self.editor_dirty = true;
+                        tx_event.send(ui::Msg::SetDirty(true)).unwrap();
                     }
                     Msg::SetActiveObject(obj) => {
                         self.select_by_name(&obj).unwrap_or_else(|e| {
@@ -372,9 +1128,55 @@ mod state {
                     }
                     Msg::SelectItem(id) => {
                         self.active_object = Some(id);
+                        self.editor_dirty = false;
+                        tx_event.send(ui::Msg::SetDirty(false)).unwrap();
                         let source = self.read_source(id).unwrap_or("".to_string());
+                        self.render_pool.execute(render::Job { id, source: source.clone() });
                         tx_event.send(ui::Msg::SetSource(source)).unwrap();
                     }
+                    Msg::ThumbnailRendered(id, png) => {
+                        self.store_thumbnail(id, &png).unwrap_or_else(|e| {
+                            log::warn!("{:?}", e); });
+                        match render::pixbuf_from_png(&png) {
+                            Ok(pixbuf) => { tx_event.send(ui::Msg::SetThumbnail(id, pixbuf)).unwrap(); }
+                            Err(e) => log::warn!("{:?}", e),
+                        }
+                    }
+                    Msg::Search(query) => {
+                        match self.search(&query) {
+                            Ok(hits) => Self::emit_items(&tx_event, hits),
+                            Err(e) => log::warn!("{:?}", e),
+                        }
+                    }
+                    Msg::Reload => {
+                        match self.search("") {
+                            Ok(hits) => Self::emit_items(&tx_event, hits),
+                            Err(e) => log::warn!("{:?}", e),
+                        }
+                        if !self.editor_dirty {
+                            if let Some(id) = self.active_object {
+                                if let Ok(source) = self.read_source(id) {
+                                    tx_event.send(ui::Msg::SetSource(source)).unwrap();
+                                }
+                            }
+                        }
+                    }
+                    Msg::Save => {
+                        log::info!("Project saved.");
+                    }
+                    Msg::SaveAs(path) => {
+                        match self.save_as(path.clone()) {
+                            Ok(()) => {
+                                self.open_file = Some(path.clone());
+                                tx_event.send(ui::Msg::SetFilename(path)).unwrap();
+                            }
+                            Err(e) => log::warn!("{:?}", e),
+                        }
+                    }
+                    Msg::ExportAll(dir, svg) => {
+                        self.export_all(&dir, svg).unwrap_or_else(|e| {
+                            log::warn!("{:?}", e); });
+                    }
                 }
             }
         }
@@ -389,8 +1191,47 @@ mod state {
     }*/
 fn main() {
     use std::sync::mpsc::channel;
+    use std::path::PathBuf;
 
     pretty_env_logger::init();
+
+    const USAGE: &str = "Usage: gnartgen <project-file> [--export <out-dir> [--svg]]";
+
+    let args: Vec<String> = std::env::args().collect();
+    let mut project: Option<PathBuf> = None;
+    let mut export_dir: Option<PathBuf> = None;
+    let mut svg = false;
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--export" => {
+                let dir = rest.next().unwrap_or_else(|| {
+                    eprintln!("--export requires a destination directory\n{}", USAGE);
+                    std::process::exit(1);
+                });
+                export_dir = Some(PathBuf::from(dir));
+            }
+            "--svg" => svg = true,
+            other if project.is_none() => project = Some(PathBuf::from(other)),
+            other => {
+                eprintln!("Unexpected argument: {}\n{}", other, USAGE);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(out_dir) = export_dir {
+        let project = project.unwrap_or_else(|| {
+            eprintln!("--export requires a project file\n{}", USAGE);
+            std::process::exit(1);
+        });
+        if let Err(e) = state::export_headless(&project, &out_dir, svg) {
+            eprintln!("Export failed: {:?}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     if gtk::init().is_err() {
         println!("Failed to initialize GTK.");
         return;
@@ -400,7 +1241,10 @@ fn main() {
     let (tx_state, rx_state) = channel();
     let (tx_event, rx_event) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
 
-    let mut state = state::State::new();
+    let mut state = state::State::new(tx_state.clone());
+    if let Some(path) = project {
+        tx_state.send(state::Msg::Open(path)).unwrap();
+    }
     std::thread::spawn(move || { state.listen(tx_event.clone(), rx_state); });
 
     let app = ui::App::new();